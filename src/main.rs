@@ -1,7 +1,8 @@
-use chrono::Local;
+use chrono::{Local, NaiveDate};
 use clap::*;
 use failure::Fail;
 use regex::*;
+use walkdir::WalkDir;
 
 use std::fmt::Display;
 use std::fs;
@@ -17,6 +18,7 @@ impl InvalidChar for char {
     fn is_invalid_for_path(&self) -> bool {
         match *self {
             '\"' | '<' | '>' | '|' | '\0' | ':' | '*' | '?' | '\\' | '/' => true,
+            c if c.is_control() => true,
             _ => false,
         }
     }
@@ -38,6 +40,10 @@ pub enum NoterError {
     CourseNotFoundError(String),
     #[fail(display = "Invalid course code {}", _0)]
     BadCourseCodeError(String),
+    #[fail(display = "Could not launch editor {}", _0)]
+    EditorError(#[cause] io::Error),
+    #[fail(display = "Could not find a matching note for course {}", _0)]
+    NoteNotFoundError(String),
 }
 
 impl From<io::Error> for NoterError {
@@ -62,7 +68,294 @@ fn extract_param(param: &str, command: &ArgMatches<'_>) -> Option<String> {
     Some(String::from(command.args.get(param)?.vals[0].to_str()?))
 }
 
+#[derive(Debug, Clone)]
+struct Note {
+    course: String,
+    date: NaiveDate,
+    title: Option<String>,
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Notes {
+    notes: Vec<Note>,
+}
+
+impl Notes {
+    fn build(root: &Path) -> Result<Notes, NoterError> {
+        let mut notes = Vec::new();
+        for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("md") {
+                continue;
+            }
+            if let Some(note) = parse_note(path) {
+                notes.push(note);
+            }
+        }
+        Ok(Notes { notes })
+    }
+
+    fn for_course(&self, course: &str) -> Vec<&Note> {
+        let mut notes: Vec<&Note> = self.notes.iter().filter(|n| n.course == course).collect();
+        notes.sort_by_key(|n| n.date);
+        notes
+    }
+
+    fn courses(&self) -> Vec<&str> {
+        let mut courses: Vec<&str> = self.notes.iter().map(|n| n.course.as_str()).collect();
+        courses.sort();
+        courses.dedup();
+        courses
+    }
+}
+
+fn parse_note(path: &Path) -> Option<Note> {
+    let file_name = path.file_name()?.to_str()?;
+    let re = Regex::new(r"^(\d{4}-\d{2}-\d{2})(?:-(.+))?\.md$").ok()?;
+    let caps = re.captures(file_name)?;
+    let date = NaiveDate::parse_from_str(&caps[1], "%Y-%m-%d").ok()?;
+    let title = caps.get(2).map(|m| m.as_str().to_string());
+
+    let course_re = Regex::new(r"^([A-Z]+[0-9]+)\s").ok()?;
+    let parent_name = path.parent()?.file_name()?.to_str()?;
+    let course = course_re.captures(parent_name)?.get(1)?.as_str().to_string();
+
+    Some(Note {
+        course,
+        date,
+        title,
+        path: path.to_path_buf(),
+    })
+}
+
+fn print_notes(notes: &[&Note]) {
+    for note in notes {
+        let title = note.title.as_deref().unwrap_or("");
+        println!(
+            "  {}::{} {}",
+            note.date.format("%F"),
+            note.path.file_name().map_or_else(|| "".into(), |s| s.to_string_lossy()),
+            title
+        );
+    }
+}
+
+fn find_note<'a>(notes: &[&'a Note], query: Option<&str>) -> Option<&'a Note> {
+    match query {
+        Some(query) => notes
+            .iter()
+            .rev()
+            .find(|n| n.date.format("%F").to_string() == query || n.title.as_deref() == Some(query))
+            .copied(),
+        None => notes.last().copied(),
+    }
+}
+
+fn default_editor() -> String {
+    if cfg!(windows) {
+        String::from("notepad")
+    } else {
+        String::from("vi")
+    }
+}
+
+fn launch_editor(path: &Path) -> Result<(), NoterError> {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| default_editor());
+    std::process::Command::new(editor)
+        .arg(path)
+        .status()
+        .map_err(NoterError::EditorError)?;
+    Ok(())
+}
+
+const CACHE_FILE: &str = ".noter-cache";
+
+fn cache_path(root: &Path) -> PathBuf {
+    root.join(CACHE_FILE)
+}
+
+fn load_cache(root: &Path) -> Vec<(String, PathBuf)> {
+    let contents = match fs::read_to_string(cache_path(root)) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, '\t');
+            let course = parts.next()?.to_string();
+            let rel_path = parts.next()?.to_string();
+            Some((course, PathBuf::from(rel_path)))
+        })
+        .collect()
+}
+
+fn save_cache(root: &Path, entries: &[(String, PathBuf)]) -> Result<(), NoterError> {
+    let mut contents = String::new();
+    for (course, rel_path) in entries {
+        contents.push_str(&format!("{}\t{}\n", course, rel_path.display()));
+    }
+    fs::write(cache_path(root), contents)?;
+    Ok(())
+}
+
+fn cache_lookup(root: &Path, course: &str) -> Option<PathBuf> {
+    let re = Regex::new(&format!(r"^({})\s.+", course)).ok()?;
+    load_cache(root).into_iter().find_map(|(cached_course, rel_path)| {
+        if cached_course != course {
+            return None;
+        }
+        let path = root.join(&rel_path);
+        if !path.exists() {
+            return None;
+        }
+        let file_name = path.file_name()?.to_str()?;
+        if !re.is_match(file_name) {
+            return None;
+        }
+        Some(path)
+    })
+}
+
+fn cache_insert(root: &Path, course: &str, path: &Path) -> Result<(), NoterError> {
+    let rel_path = path.strip_prefix(root).unwrap_or(path).to_path_buf();
+    let mut entries = load_cache(root);
+    entries.retain(|(cached_course, _)| cached_course != course);
+    entries.push((course.to_string(), rel_path));
+    save_cache(root, &entries)
+}
+
+fn rebuild_cache(root: &Path) -> Result<usize, NoterError> {
+    let re = Regex::new(r"^([A-Z]+[0-9]+)\s")?;
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !fs::metadata(&path)?.is_dir() {
+            continue;
+        }
+
+        let file_name = path.file_name().map_or_else(|| "".into(), |s| s.to_string_lossy().into_owned());
+        if let Some(caps) = re.captures(&file_name) {
+            let course = caps[1].to_string();
+            let rel_path = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            entries.push((course, rel_path));
+        }
+    }
+
+    let count = entries.len();
+    save_cache(root, &entries)?;
+    Ok(count)
+}
+
+const TEMPLATE_FILE: &str = ".noter-template.md";
+
+const DEFAULT_TEMPLATE: &str = "---\ntitle: {{title}}\ndate: {{date}}\ncourse: {{course}}\ntags: []\n---\n";
+
+fn render_template(root: &Path, course: &str, title: Option<&str>, date: &str) -> Result<String, NoterError> {
+    let template_path = root.join(TEMPLATE_FILE);
+    let template = if template_path.exists() {
+        fs::read_to_string(&template_path)?
+    } else {
+        String::from(DEFAULT_TEMPLATE)
+    };
+
+    let weekday = Local::today().format("%A").to_string();
+    Ok(template
+        .replace("{{date}}", date)
+        .replace("{{course}}", course)
+        .replace("{{title}}", title.unwrap_or(""))
+        .replace("{{weekday}}", &weekday))
+}
+
+fn human_readable_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn course_dir_size(path: &Path) -> Result<u64, NoterError> {
+    let mut size = 0u64;
+    for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            size += fs::metadata(entry.path())?.len();
+        }
+    }
+    Ok(size)
+}
+
+fn print_tree(root: &Path, notes: &Notes) -> Result<(), NoterError> {
+    let mut total_count = 0usize;
+    let mut total_size = 0u64;
+
+    for course in notes.courses() {
+        let course_notes = notes.for_course(course);
+        let course_path = find_course_path(root, course)?;
+        let course_size = course_dir_size(&course_path)?;
+
+        println!(
+            "{}/ ({} notes, {})",
+            course,
+            course_notes.len(),
+            human_readable_size(course_size)
+        );
+        for note in &course_notes {
+            let size = fs::metadata(&note.path)?.len();
+            let name = note.path.file_name().map_or_else(|| "".into(), |s| s.to_string_lossy());
+            println!("  {} ({})", name, human_readable_size(size));
+        }
+
+        total_count += course_notes.len();
+        total_size += course_size;
+    }
+
+    println!("Total: {} notes, {}", total_count, human_readable_size(total_size));
+    Ok(())
+}
+
+fn search_notes(notes: &Notes, pattern: &str, course: Option<&str>) -> Result<(), NoterError> {
+    let re = Regex::new(pattern)?;
+
+    let mut matching: Vec<&Note> = notes.notes.iter().collect();
+    if let Some(course) = course {
+        matching.retain(|n| n.course == course);
+    }
+    matching.sort_by(|a, b| (&a.course, a.date).cmp(&(&b.course, b.date)));
+
+    for note in matching {
+        let contents = fs::read_to_string(&note.path)?;
+        for (line_no, line) in contents.lines().enumerate() {
+            if re.is_match(line) {
+                println!(
+                    "{}::{}:{}: {}",
+                    note.course,
+                    note.path.file_name().map_or_else(|| "".into(), |s| s.to_string_lossy()),
+                    line_no + 1,
+                    line
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
 fn find_course_path(root: &Path, course: &str) -> Result<PathBuf, NoterError> {
+    if let Some(path) = cache_lookup(root, course) {
+        return Ok(path);
+    }
+
     let re = Regex::new(&format!(r"^({})\s.+", course))?;
     re.replace_all(course, "$course");
     for entry in fs::read_dir(root)? {
@@ -76,6 +369,7 @@ fn find_course_path(root: &Path, course: &str) -> Result<PathBuf, NoterError> {
             path.file_name()
                 .map_or_else(|| "", |s| s.to_str().unwrap_or("")),
         ) {
+            cache_insert(root, course, &path)?;
             return Ok(path);
         }
     }
@@ -91,7 +385,13 @@ fn init_matches<'a>() -> ArgMatches<'a> {
             SubCommand::with_name("new")
                 .about("Creates a new note for the course")
                 .arg(Arg::with_name("course").required(true))
-                .arg(Arg::with_name("title").required(false)),
+                .arg(Arg::with_name("title").required(false))
+                .arg(
+                    Arg::with_name("no-edit")
+                        .long("no-edit")
+                        .takes_value(false)
+                        .help("Don't launch $EDITOR after creating the note"),
+                ),
         )
         .subcommand(
             SubCommand::with_name("course")
@@ -99,6 +399,35 @@ fn init_matches<'a>() -> ArgMatches<'a> {
                 .arg(Arg::with_name("code").required(true))
                 .arg(Arg::with_name("title").required(true)),
         )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Lists notes, optionally filtered by course")
+                .arg(Arg::with_name("course").required(false)),
+        )
+        .subcommand(
+            SubCommand::with_name("edit")
+                .about("Opens an existing note in $EDITOR")
+                .arg(Arg::with_name("course").required(true))
+                .arg(
+                    Arg::with_name("query")
+                        .required(false)
+                        .help("Date (YYYY-MM-DD) or title of the note to edit; defaults to the most recent note"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Searches note contents for a regex pattern")
+                .arg(Arg::with_name("pattern").required(true))
+                .arg(Arg::with_name("course").required(false)),
+        )
+        .subcommand(
+            SubCommand::with_name("refresh")
+                .about("Rebuilds the course-path cache by rescanning the notes root"),
+        )
+        .subcommand(
+            SubCommand::with_name("tree")
+                .about("Shows note counts and sizes per course"),
+        )
         .get_matches()
 }
 
@@ -121,8 +450,10 @@ fn run() -> Result<(), NoterError> {
             }
 
             let title = extract_param("title", command);
-            let mut path = find_course_path(std::env::current_dir()?.as_path(), &course_code)?;
-            make_new_note(&mut path, &course_code, title.as_ref())?
+            let edit = !command.is_present("no-edit");
+            let root = std::env::current_dir()?;
+            let mut path = find_course_path(root.as_path(), &course_code)?;
+            make_new_note(&mut path, &course_code, title.as_ref(), edit, root.as_path())?
         }
         ("course", Some(command)) => {
             // should always be available.
@@ -136,6 +467,70 @@ fn run() -> Result<(), NoterError> {
             let mut path = PathBuf::from(std::env::current_dir()?.as_path());
             make_new_folder(&mut path, &course_code, &title)?
         }
+        ("list", Some(command)) => {
+            let root = std::env::current_dir()?;
+            let notes = Notes::build(root.as_path())?;
+
+            match extract_param("course", command) {
+                Some(course) => {
+                    let course = course.to_uppercase();
+                    if !validate_course(&course) {
+                        return Err(NoterError::BadCourseCodeError(course));
+                    }
+                    // ensure the course folder actually exists
+                    find_course_path(root.as_path(), &course)?;
+                    print_notes(&notes.for_course(&course));
+                }
+                None => {
+                    for course in notes.courses() {
+                        println!("{}:", course);
+                        print_notes(&notes.for_course(course));
+                    }
+                }
+            }
+        }
+        ("edit", Some(command)) => {
+            let course_code = extract_param("course", command).unwrap().to_uppercase();
+
+            if !validate_course(&course_code) {
+                return Err(NoterError::BadCourseCodeError(course_code));
+            }
+
+            let root = std::env::current_dir()?;
+            find_course_path(root.as_path(), &course_code)?;
+
+            let query = extract_param("query", command);
+            let notes = Notes::build(root.as_path())?;
+            let matching = notes.for_course(&course_code);
+            let note = find_note(&matching, query.as_deref())
+                .ok_or_else(|| NoterError::NoteNotFoundError(course_code.clone()))?;
+            launch_editor(&note.path)?
+        }
+        ("search", Some(command)) => {
+            let pattern = extract_param("pattern", command).unwrap();
+            let course = extract_param("course", command).map(|c| c.to_uppercase());
+            let root = std::env::current_dir()?;
+
+            if let Some(course) = &course {
+                if !validate_course(course) {
+                    return Err(NoterError::BadCourseCodeError(course.clone()));
+                }
+                find_course_path(root.as_path(), course)?;
+            }
+
+            let notes = Notes::build(root.as_path())?;
+            search_notes(&notes, &pattern, course.as_deref())?
+        }
+        ("refresh", Some(_)) => {
+            let root = std::env::current_dir()?;
+            let count = rebuild_cache(root.as_path())?;
+            println!("Refreshed cache with {} course(s).", count);
+        }
+        ("tree", Some(_)) => {
+            let root = std::env::current_dir()?;
+            let notes = Notes::build(root.as_path())?;
+            print_tree(root.as_path(), &notes)?
+        }
         _ => (),
     }
     Ok(())
@@ -151,10 +546,12 @@ fn make_new_folder<T: AsRef<str> + Display>(
     course_code: T,
     title: T,
 ) -> Result<(), NoterError> {
+    let root = path.clone();
     path.push(format!("{} {}", course_code, sanitize_file_name(title.as_ref())));
 
     if !path.exists() {
-        fs::create_dir(path)?;
+        fs::create_dir(path.as_path())?;
+        cache_insert(&root, course_code.as_ref(), path)?;
         println!("Created folder for {} {}.", course_code, title);
     } else {
         println!("Folder for {} {} already exists.", course_code, title);
@@ -166,19 +563,30 @@ fn make_new_note<T: AsRef<str> + Display>(
     path: &mut PathBuf,
     course_code: T,
     title: Option<T>,
+    edit: bool,
+    root: &Path,
 ) -> Result<(), NoterError> {
     let date = format!("{}", Local::today().format("%F"));
 
-    let new_file = title.map_or(format!("{}.md", date), |title| {
+    let new_file = title.as_ref().map_or(format!("{}.md", date), |title| {
         format!("{}-{}.md", date, sanitize_file_name(title.as_ref()))
     });
 
     path.push(&new_file);
     if path.exists() {
         println!("{}::{} already exists.", course_code, &new_file);
+        if edit {
+            launch_editor(path)?;
+        }
         return Ok(());
     }
-    fs::File::create(&path)?;
+
+    let sanitized_title = title.as_ref().map(|title| sanitize_file_name(title.as_ref()));
+    let contents = render_template(root, course_code.as_ref(), sanitized_title.as_deref(), &date)?;
+    fs::write(&path, contents)?;
     println!("Created {}::{}", course_code, &new_file);
+    if edit {
+        launch_editor(path)?;
+    }
     Ok(())
 }